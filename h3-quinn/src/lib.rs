@@ -12,26 +12,114 @@ use futures::{ready, FutureExt, StreamExt};
 
 use bytes::{Buf, Bytes};
 use quinn::{
-    generic::{IncomingBiStreams, IncomingUniStreams, NewConnection, OpenBi, OpenUni},
-    ConnectionError, VarInt, WriteError,
+    generic::{
+        Closed, Datagrams, IncomingBiStreams, IncomingUniStreams, NewConnection, OpenBi, OpenUni,
+    },
+    congestion::{CubicConfig, NewRenoConfig},
+    ConnectionError, ConnectionStats, SendDatagramError, TransportConfig, VarInt, WriteError,
+    ZeroRttAccepted,
 };
 use quinn_proto::crypto::Session;
 
 use h3::quic;
 
+#[cfg(feature = "quiche")]
+pub mod quiche;
+
+/// Send unreliable QUIC datagrams (RFC 9221) over a connection.
+///
+/// Datagrams are unordered, unreliable and size-bounded; unlike streams they
+/// carry no flow-control or retransmission, so a send may be refused without
+/// affecting the connection itself.
+pub trait SendDatagram {
+    type Error;
+
+    /// Enqueue an unreliable datagram for transmission.
+    ///
+    /// The payload must not exceed [`max_datagram_size`](Self::max_datagram_size);
+    /// oversized payloads and datagrams disabled by the peer are reported as a
+    /// [`DatagramError`] rather than tearing down the connection.
+    fn poll_send_datagram(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        data: Bytes,
+    ) -> Poll<Result<(), Self::Error>>;
+
+    /// The largest datagram the current path estimate can carry, if any.
+    fn max_datagram_size(&self) -> Option<usize>;
+}
+
+/// Receive unreliable QUIC datagrams (RFC 9221) from a connection.
+pub trait RecvDatagram {
+    type Error;
+
+    /// Poll for the next datagram sent by the peer.
+    ///
+    /// Resolves to `Ok(None)` once the connection is closed. Datagram delivery
+    /// is independent of the stream accept/open machinery and never starves it.
+    fn poll_recv_datagram(
+        &mut self,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Result<Option<Bytes>, Self::Error>>;
+}
+
 pub struct Connection<S: Session> {
     conn: quinn::generic::Connection<S>,
     incoming_bi: IncomingBiStreams<S>,
     opening_bi: Option<OpenBi<S>>,
     incoming_uni: IncomingUniStreams<S>,
     opening_uni: Option<OpenUni<S>>,
+    datagrams: Datagrams<S>,
+    zero_rtt: ZeroRtt,
+    closing: Option<Closed<S>>,
+}
+
+/// Tracks the outcome of a 0-RTT handshake.
+///
+/// A full-handshake connection is [`Disabled`](ZeroRtt::Disabled). An early-data
+/// connection starts [`Pending`](ZeroRtt::Pending) with the acceptance future and
+/// resolves to [`Accepted`](ZeroRtt::Accepted)`(bool)` once the handshake confirms
+/// whether the server kept the early data.
+enum ZeroRtt {
+    Disabled,
+    Pending(ZeroRttAccepted),
+    Accepted(bool),
 }
 
 impl<S: Session> Connection<S> {
     pub fn new(new_conn: NewConnection<S>) -> Self {
+        Self::build(new_conn, ZeroRtt::Disabled)
+    }
+
+    /// Build a connection over a 0-RTT (early data) handshake.
+    ///
+    /// Pair with [`quinn::generic::Connecting::into_0rtt`]: on success it yields
+    /// the [`NewConnection`] usable for early data immediately, plus a
+    /// [`ZeroRttAccepted`] future that resolves once the handshake confirms
+    /// whether the server accepted the early data. Query the outcome through
+    /// [`poll_zero_rtt_accepted`](Self::poll_zero_rtt_accepted) so the h3 layer
+    /// can decide whether to replay idempotent requests.
+    ///
+    /// **0-RTT data is replay-vulnerable**: an attacker may capture and resend
+    /// it, so only requests that are safe to execute more than once should be
+    /// sent before acceptance is confirmed.
+    ///
+    /// This wrapper does not itself cache or reuse TLS session tickets — it
+    /// only surfaces whichever [`NewConnection`] the caller already obtained
+    /// from [`into_0rtt`](quinn::generic::Connecting::into_0rtt). Getting a
+    /// `NewConnection` usable for 0-RTT in the first place depends on the
+    /// caller's [`quinn::ClientConfig`] carrying a ticket from a prior
+    /// connection to the same server; without one, `into_0rtt` falls back to
+    /// a full 1-RTT handshake before this method is ever reached.
+    pub fn new_0rtt(new_conn: NewConnection<S>, accepted: ZeroRttAccepted) -> Self {
+        Self::build(new_conn, ZeroRtt::Pending(accepted))
+    }
+
+    fn build(new_conn: NewConnection<S>, zero_rtt: ZeroRtt) -> Self {
         let NewConnection {
             uni_streams,
             bi_streams,
+            datagrams,
             connection,
             ..
         } = new_conn;
@@ -42,6 +130,141 @@ impl<S: Session> Connection<S> {
             opening_bi: None,
             incoming_uni: uni_streams,
             opening_uni: None,
+            datagrams,
+            zero_rtt,
+            closing: None,
+        }
+    }
+
+    /// Live statistics for the connection's path.
+    ///
+    /// Surfaces Quinn's [`ConnectionStats`] — smoothed RTT, congestion window,
+    /// bytes in flight, packets lost, PTO count — so telemetry- or
+    /// bitrate-adaptive applications can observe the path without reaching
+    /// around this abstraction into Quinn.
+    pub fn stats(&self) -> ConnectionStats {
+        self.conn.stats()
+    }
+
+    /// The peer's current UDP address (may change across connection migration).
+    pub fn remote_address(&self) -> std::net::SocketAddr {
+        self.conn.remote_address()
+    }
+
+    /// The negotiated maximum unreliable datagram size, if datagrams are enabled.
+    pub fn max_datagram_size(&self) -> Option<usize> {
+        self.conn.max_datagram_size()
+    }
+
+    /// Close the whole connection with an HTTP/3 application error code and
+    /// reason string, mapping to [`quinn::generic::Connection::close`].
+    ///
+    /// Returns [`InvalidErrorCode`] if `error_code` does not fit in a 62-bit
+    /// QUIC varint, so an out-of-range `H3_*` code is never silently clamped.
+    pub fn close(&self, error_code: u64, reason: &[u8]) -> Result<(), InvalidErrorCode> {
+        self.conn.close(varint(error_code)?, reason);
+        Ok(())
+    }
+
+    /// Resolve once the connection is closed, yielding the peer's
+    /// [`ConnectionError`], which distinguishes an application close
+    /// ([`ApplicationClosed`](ConnectionError::ApplicationClosed)), a transport
+    /// error ([`ConnectionClosed`](ConnectionError::ConnectionClosed)), and an
+    /// idle timeout ([`TimedOut`](ConnectionError::TimedOut)).
+    ///
+    /// Backed by [`quinn::generic::Connection::closed`], a dedicated future
+    /// independent of the stream acceptors — so this can be polled alongside
+    /// [`poll_accept_bidi_stream`](quic::Connection::poll_accept_bidi_stream)
+    /// without the two competing over the same stream or waker slot.
+    pub fn poll_closed(&mut self, cx: &mut task::Context<'_>) -> Poll<ConnectionError> {
+        if self.closing.is_none() {
+            self.closing = Some(self.conn.closed());
+        }
+        let reason = ready!(self.closing.as_mut().unwrap().poll_unpin(cx));
+        Poll::Ready(reason)
+    }
+
+    /// Poll whether the server accepted this connection's 0-RTT early data.
+    ///
+    /// Resolves immediately with `false` for a full-handshake connection, and
+    /// with the server's decision once the handshake completes for a 0-RTT one.
+    pub fn poll_zero_rtt_accepted(&mut self, cx: &mut task::Context<'_>) -> Poll<bool> {
+        match self.zero_rtt {
+            ZeroRtt::Disabled => Poll::Ready(false),
+            ZeroRtt::Accepted(accepted) => Poll::Ready(accepted),
+            ZeroRtt::Pending(ref mut fut) => {
+                let accepted = ready!(fut.poll_unpin(cx));
+                self.zero_rtt = ZeroRtt::Accepted(accepted);
+                Poll::Ready(accepted)
+            }
+        }
+    }
+}
+
+impl<S: Session> SendDatagram for Connection<S> {
+    type Error = DatagramError;
+
+    fn poll_send_datagram(
+        &mut self,
+        _cx: &mut task::Context<'_>,
+        data: Bytes,
+    ) -> Poll<Result<(), Self::Error>> {
+        // Quinn buffers a single datagram synchronously; there is no backpressure
+        // future to await, so we resolve immediately either way.
+        Poll::Ready(self.conn.send_datagram(data).map_err(Into::into))
+    }
+
+    fn max_datagram_size(&self) -> Option<usize> {
+        self.conn.max_datagram_size()
+    }
+}
+
+impl<S: Session> RecvDatagram for Connection<S> {
+    type Error = ConnectionError;
+
+    fn poll_recv_datagram(
+        &mut self,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Result<Option<Bytes>, Self::Error>> {
+        match ready!(self.datagrams.poll_next_unpin(cx)) {
+            Some(x) => Poll::Ready(x.map(Some)),
+            None => Poll::Ready(Ok(None)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DatagramError {
+    /// The payload exceeded the peer's or path's maximum datagram size.
+    TooLarge,
+    /// The peer did not advertise datagram support, or datagrams are disabled
+    /// locally.
+    Unsupported,
+    /// The connection was lost while sending.
+    ConnectionLost(ConnectionError),
+}
+
+impl std::error::Error for DatagramError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::ConnectionLost(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Display for DatagramError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl From<SendDatagramError> for DatagramError {
+    fn from(e: SendDatagramError) -> Self {
+        match e {
+            SendDatagramError::TooLarge => Self::TooLarge,
+            SendDatagramError::UnsupportedByPeer | SendDatagramError::Disabled => Self::Unsupported,
+            SendDatagramError::ConnectionLost(e) => Self::ConnectionLost(e),
         }
     }
 }
@@ -109,6 +332,65 @@ where
     }
 }
 
+/// Congestion controller to install on a connection's [`TransportConfig`].
+///
+/// BBR is intentionally absent: the quinn version this adapter targets ships
+/// only NewReno and Cubic. Select the controller at endpoint construction
+/// time via [`transport_config`], before the [`NewConnection`] reaches
+/// [`Connection::new`].
+#[derive(Debug, Clone, Copy)]
+pub enum CongestionController {
+    NewReno,
+    Cubic,
+}
+
+/// Build a [`TransportConfig`] with the chosen congestion controller.
+///
+/// Apply it to a `quinn::ClientConfig`/`ServerConfig` before binding the
+/// endpoint. Pacing is always on in this quinn version and isn't
+/// independently toggleable, so there's no parameter for it here.
+///
+/// UDP socket options such as ECN and send/receive buffer sizes aren't
+/// covered by this function or anything else in this module: this crate only
+/// wraps the per-connection `quinn::generic::Connection`, never the
+/// `quinn::Endpoint` that owns the bound socket, and this quinn version gives
+/// the endpoint no option-setting surface after binding. Set them with
+/// `socket2` (or the platform's raw `setsockopt`) on the `std::net::UdpSocket`
+/// before handing it to the endpoint builder, if the builder you're using
+/// accepts a pre-bound socket.
+pub fn transport_config(controller: CongestionController) -> TransportConfig {
+    let mut config = TransportConfig::default();
+    match controller {
+        CongestionController::NewReno => {
+            config.congestion_controller_factory(std::sync::Arc::new(NewRenoConfig::default()))
+        }
+        CongestionController::Cubic => {
+            config.congestion_controller_factory(std::sync::Arc::new(CubicConfig::default()))
+        }
+    };
+    config
+}
+
+/// Convert an application error code into a QUIC varint, rejecting values that
+/// exceed the 62-bit range rather than clamping them.
+fn varint(code: u64) -> Result<VarInt, InvalidErrorCode> {
+    VarInt::from_u64(code).map_err(|_| InvalidErrorCode { code })
+}
+
+/// An error code did not fit in the 62-bit range of a QUIC varint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidErrorCode {
+    pub code: u64,
+}
+
+impl Display for InvalidErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "error code {} exceeds the 62-bit varint range", self.code)
+    }
+}
+
+impl Error for InvalidErrorCode {}
+
 pub struct BidiStream<B, S>
 where
     B: Buf,
@@ -177,18 +459,23 @@ where
 
 pub struct RecvStream<S: Session> {
     stream: quinn::generic::RecvStream<S>,
-    offset: u64,
-    chunks: BTreeMap<u64, Bytes>,
+    reassembler: Reassembler,
 }
 
 impl<S: Session> RecvStream<S> {
     fn new(stream: quinn::generic::RecvStream<S>) -> Self {
         Self {
             stream,
-            offset: 0,
-            chunks: BTreeMap::new(),
+            reassembler: Reassembler::new(),
         }
     }
+
+    /// Stop receiving, returning [`InvalidErrorCode`] if `error_code` does not
+    /// fit in a 62-bit varint instead of panicking on an out-of-range code.
+    pub fn try_stop_sending(&mut self, error_code: u64) -> Result<(), InvalidErrorCode> {
+        let _ = self.stream.stop(varint(error_code)?);
+        Ok(())
+    }
 }
 
 impl<S: Session> quic::RecvStream for RecvStream<S> {
@@ -199,44 +486,86 @@ impl<S: Session> quic::RecvStream for RecvStream<S> {
         &mut self,
         cx: &mut task::Context<'_>,
     ) -> Poll<Result<Option<Self::Buf>, Self::Error>> {
-        let ret = match self.stream.read_unordered().poll_unpin(cx) {
-            Poll::Pending => Poll::Pending,
-            Poll::Ready(Ok(None)) => Poll::Ready(Ok(None)),
-            Poll::Ready(Err(e)) => return Poll::Ready(Err(e.into())),
-            // If we get the chunk we're looking for, return it right away
-            Poll::Ready(Ok(Some((mut chunk, offset)))) if offset <= self.offset => {
-                chunk.advance((self.offset - offset) as usize); // XXX overflow
-                self.offset += chunk.len() as u64;
+        loop {
+            // Hand back the next already-contiguous chunk before reading more, so
+            // a buffered run drains fully instead of stalling behind the socket.
+            if let Some(chunk) = self.reassembler.pop() {
                 return Poll::Ready(Ok(Some(chunk)));
             }
-            // A chunk beyond current offset gets saved
-            Poll::Ready(Ok(Some((data, offset)))) => {
-                self.chunks.insert(offset, data);
-                Poll::Pending
+
+            match ready!(self.stream.read_unordered().poll_unpin(cx)) {
+                Ok(None) => return Poll::Ready(Ok(None)),
+                Ok(Some((chunk, offset))) => self.reassembler.push(chunk, offset),
+                Err(e) => return Poll::Ready(Err(e.into())),
             }
-        };
+        }
+    }
 
-        // Nothing we've read can be yeilded, but we could have some chunk corresponding to `offset`
-        let chunk_key = self
-            .chunks
-            .keys()
-            .take_while(|x| **x <= self.offset)
-            .next()
-            .copied();
-        if let Some(offset) = chunk_key {
-            let mut chunk = self.chunks.remove(&offset).unwrap();
-            chunk.advance((self.offset - offset) as usize); // XXX overflow
-            self.offset += chunk.len() as u64;
-            return Poll::Ready(Ok(Some(chunk)));
-        };
+    fn stop_sending(&mut self, error_code: u64) {
+        // Out-of-range codes are dropped rather than clamped; use
+        // [`try_stop_sending`](Self::try_stop_sending) to observe the error.
+        let _ = self.try_stop_sending(error_code);
+    }
+}
+
+/// Re-imposes stream order onto the out-of-order chunks produced by
+/// [`RecvStream::read_unordered`](quinn::generic::RecvStream::read_unordered).
+///
+/// Chunks are keyed by their start offset. Duplicates that fall entirely below
+/// the delivered offset are dropped; partial overlaps are trimmed; and gaps are
+/// held until the intervening bytes arrive.
+struct Reassembler {
+    offset: u64,
+    chunks: BTreeMap<u64, Bytes>,
+}
+
+impl Reassembler {
+    fn new() -> Self {
+        Self {
+            offset: 0,
+            chunks: BTreeMap::new(),
+        }
+    }
 
-        ret
+    /// Buffer a freshly read chunk. Pure duplicates (entirely below the current
+    /// offset) are discarded, as is a chunk no longer than one already buffered
+    /// at the same offset; everything else is kept for [`pop`](Self::pop).
+    fn push(&mut self, chunk: Bytes, offset: u64) {
+        if offset + chunk.len() as u64 <= self.offset {
+            // Retransmit we've already delivered in full.
+            return;
+        }
+        match self.chunks.get(&offset) {
+            // A buffered chunk at this offset already covers these bytes.
+            Some(existing) if existing.len() >= chunk.len() => {}
+            _ => {
+                self.chunks.insert(offset, chunk);
+            }
+        }
     }
 
-    fn stop_sending(&mut self, error_code: u64) {
-        let _ = self
-            .stream
-            .stop(VarInt::from_u64(error_code).expect("invalid error_code"));
+    /// Yield the next chunk that is contiguous with the delivered offset,
+    /// trimming any overlap, or `None` if the head of the buffer is still gapped.
+    fn pop(&mut self) -> Option<Bytes> {
+        while let Some((&offset, _)) = self.chunks.iter().next() {
+            if offset > self.offset {
+                // Head of the buffer sits past a gap; wait for the missing bytes.
+                return None;
+            }
+
+            let mut chunk = self.chunks.remove(&offset).unwrap();
+            if offset + chunk.len() as u64 <= self.offset {
+                // Fully superseded by what we've already delivered.
+                continue;
+            }
+            if offset < self.offset {
+                chunk.advance((self.offset - offset) as usize);
+            }
+            self.offset += chunk.len() as u64;
+            return Some(chunk);
+        }
+
+        None
     }
 }
 
@@ -280,6 +609,13 @@ where
             writing: None,
         }
     }
+
+    /// Reset the stream, returning [`InvalidErrorCode`] if `reset_code` does not
+    /// fit in a 62-bit varint instead of clamping it to [`VarInt::MAX`].
+    pub fn try_reset(&mut self, reset_code: u64) -> Result<(), InvalidErrorCode> {
+        let _ = self.stream.reset(varint(reset_code)?);
+        Ok(())
+    }
 }
 
 impl<B, S> quic::SendStream<B> for SendStream<B, S>
@@ -302,9 +638,9 @@ where
     }
 
     fn reset(&mut self, reset_code: u64) {
-        let _ = self
-            .stream
-            .reset(VarInt::from_u64(reset_code).unwrap_or(VarInt::MAX));
+        // Out-of-range codes are dropped rather than clamped to `VarInt::MAX`;
+        // use [`try_reset`](Self::try_reset) to observe the error.
+        let _ = self.try_reset(reset_code);
     }
 
     fn send_data(&mut self, data: B) -> Result<(), Self::Error> {
@@ -402,4 +738,62 @@ mod tests {
         let cert = Certificate::from_der(&cert.serialize_der().unwrap()).unwrap();
         (CertificateChain::from_certs(vec![cert.clone()]), cert, key)
     }
+
+    fn drain(r: &mut Reassembler) -> Vec<u8> {
+        let mut out = Vec::new();
+        while let Some(chunk) = r.pop() {
+            out.extend_from_slice(&chunk);
+        }
+        out
+    }
+
+    #[test]
+    fn reassembles_in_order() {
+        let mut r = Reassembler::new();
+        r.push(Bytes::from_static(b"hello "), 0);
+        r.push(Bytes::from_static(b"world"), 6);
+        assert_eq!(drain(&mut r), b"hello world");
+    }
+
+    #[test]
+    fn buffers_gapped_delivery_until_contiguous() {
+        let mut r = Reassembler::new();
+        r.push(Bytes::from_static(b"world"), 6);
+        // The tail is buffered while the head is still missing.
+        assert!(r.pop().is_none());
+        r.push(Bytes::from_static(b"hello "), 0);
+        assert_eq!(drain(&mut r), b"hello world");
+    }
+
+    #[test]
+    fn drops_pure_duplicates() {
+        let mut r = Reassembler::new();
+        r.push(Bytes::from_static(b"hello "), 0);
+        assert_eq!(&r.pop().unwrap()[..], b"hello ");
+        // A full retransmit of already-delivered bytes yields nothing.
+        r.push(Bytes::from_static(b"hello "), 0);
+        assert!(r.pop().is_none());
+    }
+
+    #[test]
+    fn rejects_out_of_range_error_codes() {
+        assert!(varint(0).is_ok());
+        assert!(varint((1 << 62) - 1).is_ok());
+        assert_eq!(
+            varint(1 << 62).unwrap_err(),
+            InvalidErrorCode { code: 1 << 62 }
+        );
+        assert!(varint(u64::MAX).is_err());
+    }
+
+    #[test]
+    fn trims_overlapping_chunks() {
+        let mut r = Reassembler::new();
+        r.push(Bytes::from_static(b"hello "), 0);
+        assert_eq!(&r.pop().unwrap()[..], b"hello ");
+        // Starts at offset 4 ('o' of "hello "), overlapping the 2 bytes
+        // already delivered; only the fresh suffix is yielded.
+        r.push(Bytes::from_static(b"o world"), 4);
+        assert_eq!(&r.pop().unwrap()[..], b"world");
+    }
 }
@@ -0,0 +1,516 @@
+//! QUIC Transport implementation with quiche
+//!
+//! This module implements the same `h3::quic` traits as the Quinn adapter, but
+//! on top of Cloudflare's [quiche]. quiche owns a single connection object that
+//! is fed raw UDP datagrams and read/written per stream-id, so the adapter runs
+//! an internal [`Driver`] that pumps a UDP socket — `recv()`ing inbound packets
+//! into the connection and `send()`ing the outbound ones — and surfaces
+//! readable/writable stream-ids through the transport-agnostic poll surface.
+//!
+//! [quiche]: https://github.com/cloudflare/quiche
+use std::{
+    collections::{HashSet, VecDeque},
+    error::Error,
+    fmt::Display,
+    future::Future as _,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    task::{self, Poll, Waker},
+    time::Duration,
+};
+
+use bytes::{Buf, Bytes};
+use tokio::net::UdpSocket;
+
+use h3::quic;
+
+const MAX_DATAGRAM_SIZE: usize = 1350;
+
+/// Shared connection state driven by [`Driver`] and observed by the stream
+/// wrappers. All access goes through the `Mutex`; the driver wakes whichever
+/// tasks registered interest once a packet changes readability/writability.
+struct Shared {
+    conn: quiche::Connection,
+    /// Stream-ids opened by the peer and not yet accepted by the application.
+    incoming_bi: VecDeque<u64>,
+    incoming_uni: VecDeque<u64>,
+    /// Peer-initiated stream-ids already surfaced to the application, either
+    /// still queued in `incoming_bi`/`incoming_uni` or already accepted. A
+    /// stream with buffered-but-unread data stays `readable()` on every later
+    /// poll, so without this the driver would enqueue — and hand out — the
+    /// same id more than once.
+    known_streams: HashSet<u64>,
+    /// Next client-initiated stream-id to hand out, per directionality.
+    next_bi: u64,
+    next_uni: u64,
+    /// Tasks to wake when the driver makes progress.
+    readable: Vec<Waker>,
+    writable: Vec<Waker>,
+    /// Separate waker slots so a pending bidi accept and a pending uni accept
+    /// don't clobber each other's registration.
+    accept_bi: Option<Waker>,
+    accept_uni: Option<Waker>,
+}
+
+impl Shared {
+    fn wake_readable(&mut self) {
+        for w in self.readable.drain(..) {
+            w.wake();
+        }
+    }
+
+    fn wake_writable(&mut self) {
+        for w in self.writable.drain(..) {
+            w.wake();
+        }
+    }
+}
+
+pub struct Connection {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Connection {
+    /// Wrap an established quiche connection and spawn its packet-pump driver.
+    ///
+    /// `socket` must already be bound; its local address is read once here to
+    /// stamp every [`quiche::RecvInfo`].
+    pub fn new(conn: quiche::Connection, socket: UdpSocket, peer: SocketAddr) -> Self {
+        let local = socket
+            .local_addr()
+            .expect("socket passed to Connection::new must be bound");
+        let shared = Arc::new(Mutex::new(Shared {
+            conn,
+            incoming_bi: VecDeque::new(),
+            incoming_uni: VecDeque::new(),
+            known_streams: HashSet::new(),
+            next_bi: 0,
+            next_uni: 2,
+            readable: Vec::new(),
+            writable: Vec::new(),
+            accept_bi: None,
+            accept_uni: None,
+        }));
+        tokio::spawn(Driver {
+            shared: shared.clone(),
+            socket,
+            peer,
+            local,
+            pending_send: None,
+            // Fires immediately so the first poll arms it to quiche's real
+            // deadline; `on_timeout` is a no-op until a timer is actually set.
+            timer: Box::pin(tokio::time::sleep(Duration::ZERO)),
+        });
+        Self { shared }
+    }
+}
+
+impl<B> quic::Connection<B> for Connection
+where
+    B: Buf,
+{
+    type SendStream = SendStream<B>;
+    type RecvStream = RecvStream;
+    type BidiStream = BidiStream<B>;
+    type Error = ConnectionError;
+
+    fn poll_accept_bidi_stream(
+        &mut self,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Result<Option<Self::BidiStream>, Self::Error>> {
+        let mut shared = self.shared.lock().unwrap();
+        // Drain whatever the driver already queued before giving up on a
+        // closed connection, so a stream accepted-but-not-yet-handed-out
+        // isn't dropped the moment the peer goes away.
+        match shared.incoming_bi.pop_front() {
+            Some(id) => Poll::Ready(Ok(Some(BidiStream {
+                send: SendStream::new(id, self.shared.clone()),
+                recv: RecvStream::new(id, self.shared.clone()),
+            }))),
+            None if shared.conn.is_closed() => Poll::Ready(Ok(None)),
+            None => {
+                shared.accept_bi = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    fn poll_open_bidi_stream(
+        &mut self,
+        _cx: &mut task::Context<'_>,
+    ) -> Poll<Result<Self::BidiStream, Self::Error>> {
+        let mut shared = self.shared.lock().unwrap();
+        let id = shared.next_bi;
+        shared.next_bi += 4;
+        Poll::Ready(Ok(BidiStream {
+            send: SendStream::new(id, self.shared.clone()),
+            recv: RecvStream::new(id, self.shared.clone()),
+        }))
+    }
+
+    fn poll_accept_recv_stream(
+        &mut self,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Result<Option<Self::RecvStream>, Self::Error>> {
+        let mut shared = self.shared.lock().unwrap();
+        // See poll_accept_bidi_stream: drain the queue before honoring close.
+        match shared.incoming_uni.pop_front() {
+            Some(id) => Poll::Ready(Ok(Some(RecvStream::new(id, self.shared.clone())))),
+            None if shared.conn.is_closed() => Poll::Ready(Ok(None)),
+            None => {
+                shared.accept_uni = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    fn poll_open_send_stream(
+        &mut self,
+        _cx: &mut task::Context<'_>,
+    ) -> Poll<Result<Self::SendStream, Self::Error>> {
+        let mut shared = self.shared.lock().unwrap();
+        let id = shared.next_uni;
+        shared.next_uni += 4;
+        Poll::Ready(Ok(SendStream::new(id, self.shared.clone())))
+    }
+}
+
+pub struct BidiStream<B: Buf> {
+    send: SendStream<B>,
+    recv: RecvStream,
+}
+
+impl<B: Buf> quic::BidiStream<B> for BidiStream<B> {
+    type SendStream = SendStream<B>;
+    type RecvStream = RecvStream;
+
+    fn split(self) -> (Self::SendStream, Self::RecvStream) {
+        (self.send, self.recv)
+    }
+}
+
+impl<B: Buf> quic::RecvStream for BidiStream<B> {
+    type Buf = Bytes;
+    type Error = ReadError;
+
+    fn poll_data(
+        &mut self,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Result<Option<Self::Buf>, Self::Error>> {
+        self.recv.poll_data(cx)
+    }
+
+    fn stop_sending(&mut self, error_code: u64) {
+        self.recv.stop_sending(error_code)
+    }
+}
+
+impl<B: Buf> quic::SendStream<B> for BidiStream<B> {
+    type Error = SendStreamError;
+
+    fn poll_ready(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.send.poll_ready(cx)
+    }
+
+    fn poll_finish(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.send.poll_finish(cx)
+    }
+
+    fn reset(&mut self, reset_code: u64) {
+        self.send.reset(reset_code)
+    }
+
+    fn send_data(&mut self, data: B) -> Result<(), Self::Error> {
+        self.send.send_data(data)
+    }
+}
+
+pub struct RecvStream {
+    id: u64,
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl RecvStream {
+    fn new(id: u64, shared: Arc<Mutex<Shared>>) -> Self {
+        Self { id, shared }
+    }
+}
+
+impl quic::RecvStream for RecvStream {
+    type Buf = Bytes;
+    type Error = ReadError;
+
+    fn poll_data(
+        &mut self,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Result<Option<Self::Buf>, Self::Error>> {
+        let mut shared = self.shared.lock().unwrap();
+        let mut buf = [0u8; MAX_DATAGRAM_SIZE];
+        match shared.conn.stream_recv(self.id, &mut buf) {
+            Ok((len, fin)) => {
+                if len == 0 && fin {
+                    Poll::Ready(Ok(None))
+                } else {
+                    Poll::Ready(Ok(Some(Bytes::copy_from_slice(&buf[..len]))))
+                }
+            }
+            Err(quiche::Error::Done) => {
+                // Nothing buffered for this stream yet; the driver will wake us.
+                shared.readable.push(cx.waker().clone());
+                Poll::Pending
+            }
+            Err(quiche::Error::StreamReset(code)) => {
+                Poll::Ready(Err(ReadError::Reset(code)))
+            }
+            Err(e) => Poll::Ready(Err(ReadError::Connection(e))),
+        }
+    }
+
+    fn stop_sending(&mut self, error_code: u64) {
+        let mut shared = self.shared.lock().unwrap();
+        let _ = shared
+            .conn
+            .stream_shutdown(self.id, quiche::Shutdown::Read, error_code);
+    }
+}
+
+#[derive(Debug)]
+pub enum ReadError {
+    Reset(u64),
+    Connection(quiche::Error),
+}
+
+impl Display for ReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for ReadError {}
+
+pub struct SendStream<B: Buf> {
+    id: u64,
+    shared: Arc<Mutex<Shared>>,
+    writing: Option<B>,
+}
+
+impl<B: Buf> SendStream<B> {
+    fn new(id: u64, shared: Arc<Mutex<Shared>>) -> Self {
+        Self {
+            id,
+            shared,
+            writing: None,
+        }
+    }
+}
+
+impl<B: Buf> quic::SendStream<B> for SendStream<B> {
+    type Error = SendStreamError;
+
+    fn poll_ready(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let data = match self.writing {
+            Some(ref mut data) => data,
+            None => return Poll::Ready(Ok(())),
+        };
+
+        let mut shared = self.shared.lock().unwrap();
+        while data.has_remaining() {
+            match shared.conn.stream_send(self.id, data.bytes(), false) {
+                Ok(n) => data.advance(n),
+                // The send buffer is full; the driver flushes and re-arms us.
+                Err(quiche::Error::Done) => {
+                    shared.writable.push(cx.waker().clone());
+                    return Poll::Pending;
+                }
+                Err(e) => return Poll::Ready(Err(SendStreamError::Connection(e))),
+            }
+        }
+        self.writing = None;
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_finish(&mut self, _cx: &mut task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let mut shared = self.shared.lock().unwrap();
+        match shared.conn.stream_send(self.id, &[], true) {
+            Ok(_) => Poll::Ready(Ok(())),
+            Err(e) => Poll::Ready(Err(SendStreamError::Connection(e))),
+        }
+    }
+
+    fn reset(&mut self, reset_code: u64) {
+        let mut shared = self.shared.lock().unwrap();
+        let _ = shared
+            .conn
+            .stream_shutdown(self.id, quiche::Shutdown::Write, reset_code);
+    }
+
+    fn send_data(&mut self, data: B) -> Result<(), Self::Error> {
+        if self.writing.is_some() {
+            return Err(SendStreamError::NotReady);
+        }
+        self.writing = Some(data);
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum SendStreamError {
+    Connection(quiche::Error),
+    NotReady,
+}
+
+impl Display for SendStreamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for SendStreamError {}
+
+#[derive(Debug)]
+pub struct ConnectionError(quiche::Error);
+
+impl Display for ConnectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Error for ConnectionError {}
+
+/// Pumps the UDP socket: feeds inbound packets into the connection, flushes the
+/// outbound ones, fires quiche's internal timer, and republishes
+/// readable/writable stream-ids to the waiting stream wrappers. Lives for the
+/// lifetime of the connection as a spawned task.
+struct Driver {
+    shared: Arc<Mutex<Shared>>,
+    socket: UdpSocket,
+    peer: SocketAddr,
+    /// `socket`'s local address, read once at construction; stamped onto
+    /// every [`quiche::RecvInfo`].
+    local: SocketAddr,
+    /// A packet quiche already produced via `conn.send()` but the socket
+    /// wasn't ready to accept; retried before asking quiche for a new one so
+    /// bytes quiche's loss/congestion accounting believes were sent actually
+    /// reach the wire. Carries the destination quiche picked, which may
+    /// differ from `peer` across a connection migration.
+    pending_send: Option<(Vec<u8>, usize, SocketAddr)>,
+    /// Armed to quiche's next `conn.timeout()` deadline; firing calls
+    /// `conn.on_timeout()` so retransmission, PTO and the idle timeout
+    /// actually happen.
+    timer: std::pin::Pin<Box<tokio::time::Sleep>>,
+}
+
+impl std::future::Future for Driver {
+    type Output = ();
+
+    fn poll(self: std::pin::Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        let mut buf = [0u8; MAX_DATAGRAM_SIZE];
+
+        // Drain whatever the socket has ready into the connection, dropping
+        // anything that didn't come from the peer this connection is with.
+        while let Poll::Ready(Ok((len, from))) = this.socket.poll_recv_from(cx, &mut buf) {
+            if from != this.peer {
+                continue;
+            }
+            let mut shared = this.shared.lock().unwrap();
+            let info = quiche::RecvInfo {
+                from,
+                to: this.local,
+            };
+            let _ = shared.conn.recv(&mut buf[..len], info);
+        }
+
+        let mut shared = this.shared.lock().unwrap();
+
+        // Every `Connection`/stream handle has been dropped without the peer
+        // closing first (this task's own clone is always the last one left);
+        // say goodbye and stop driving instead of looping on this socket
+        // forever.
+        if Arc::strong_count(&this.shared) == 1 {
+            let _ = shared.conn.close(false, 0x0, b"");
+            let mut out = vec![0u8; MAX_DATAGRAM_SIZE];
+            if let Ok((len, info)) = shared.conn.send(&mut out) {
+                let _ = this.socket.poll_send_to(cx, &out[..len], info.to);
+            }
+            return Poll::Ready(());
+        }
+
+        // Drive quiche's timer: fire it if it elapsed, then re-arm to the
+        // (possibly new) next deadline. Without this quiche never emits
+        // retransmissions or notices the idle timeout.
+        if this.timer.as_mut().poll(cx).is_ready() {
+            shared.conn.on_timeout();
+        }
+        match shared.conn.timeout() {
+            Some(d) => this.timer.as_mut().reset(tokio::time::Instant::now() + d),
+            None => this
+                .timer
+                .as_mut()
+                .reset(tokio::time::Instant::now() + Duration::from_secs(3600)),
+        }
+
+        // Surface freshly readable streams, skipping ids already handed to
+        // the application (queued or accepted) on an earlier poll.
+        let readable: HashSet<u64> = shared.conn.readable().collect();
+        for id in readable {
+            // Streams this side opened are never accepted; bit 0 of the id is
+            // set for server-initiated streams.
+            let peer_initiated = (id & 1 == 1) != shared.conn.is_server();
+            if !peer_initiated || !shared.known_streams.insert(id) {
+                continue;
+            }
+            if id % 4 < 2 {
+                shared.incoming_bi.push_back(id);
+            } else {
+                shared.incoming_uni.push_back(id);
+            }
+        }
+        // Also wake a pending accept on close, so it can observe
+        // `conn.is_closed()` rather than stall forever.
+        if !shared.incoming_bi.is_empty() || shared.conn.is_closed() {
+            if let Some(w) = shared.accept_bi.take() {
+                w.wake();
+            }
+        }
+        if !shared.incoming_uni.is_empty() || shared.conn.is_closed() {
+            if let Some(w) = shared.accept_uni.take() {
+                w.wake();
+            }
+        }
+        shared.wake_readable();
+        shared.wake_writable();
+
+        // Flush any packets the connection wants to send, retrying a
+        // previously-produced packet before pulling a new one from quiche.
+        loop {
+            let (buf, len, to) = match this.pending_send.take() {
+                Some(pending) => pending,
+                None => {
+                    let mut out = vec![0u8; MAX_DATAGRAM_SIZE];
+                    match shared.conn.send(&mut out) {
+                        Ok((len, info)) => (out, len, info.to),
+                        Err(quiche::Error::Done) => break,
+                        // A connection-level send error; `is_closed()` below
+                        // will tear the driver down.
+                        Err(_) => break,
+                    }
+                }
+            };
+            match this.socket.poll_send_to(cx, &buf[..len], to) {
+                Poll::Ready(Ok(_)) => continue,
+                Poll::Ready(Err(_)) | Poll::Pending => {
+                    this.pending_send = Some((buf, len, to));
+                    break;
+                }
+            }
+        }
+
+        if shared.conn.is_closed() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}